@@ -2,11 +2,16 @@ extern crate byteorder;
 extern crate getopts;
 extern crate image;
 extern crate sdfgen;
+extern crate rayon;
+extern crate tiff;
+
+mod png_filter;
 
 use std::fs::File;
 use std::io::Write;
 
 use image::GrayImage;
+use image::Luma;
 
 use getopts::Options;
 
@@ -14,11 +19,12 @@ use byteorder::{LittleEndian, WriteBytesExt};
 
 use image::ImageEncoder;
 use sdfgen::functions::bit_compressor;
-use sdfgen::functions::bw_to_bits;
 use sdfgen::sdf_algorithm::calculate_sdf;
 use sdfgen::sdf_algorithm::sdf_to_grayscale_image;
 use sdfgen::sdf_algorithm::DstT;
 
+use tiff::encoder::{colortype, compression, TiffEncoder};
+
 fn print_usage(program: &String, opts: &Options) {
     let brief = format!(
         "Usage: {} [options] inputimage.png outputimage.png",
@@ -27,6 +33,309 @@ fn print_usage(program: &String, opts: &Options) {
     print!("{}", opts.usage(&brief));
 }
 
+// Replaces sdfgen::functions::bw_to_bits with a configurable threshold so
+// `--threshold` can move where the input is split into "inside"/"outside".
+fn binarize(v: u8, threshold: u8) -> u8 {
+    if v >= threshold {
+        1
+    } else {
+        0
+    }
+}
+
+// Finds the closest 0.5-coverage crossing to (cx, cy) among every
+// axis-aligned pixel pair within `radius` coverage pixels of it, returning
+// the Euclidean distance to that crossing in input pixels, or None if
+// nothing in range straddles `threshold`. `radius` has to cover the whole
+// input-pixel block a downsampled output sample stands for — a fixed
+// 1-pixel radius would miss crossings once `sdf_size` is more than a
+// couple of mip levels coarser than the input.
+fn nearest_coverage_crossing(
+    coverage: &GrayImage,
+    cx: f64,
+    cy: f64,
+    threshold: u8,
+    radius: i64,
+) -> Option<f64> {
+    let (w, h) = coverage.dimensions();
+    let in_bounds = |x: i64, y: i64| x >= 0 && y >= 0 && (x as u32) < w && (y as u32) < h;
+    let (cxi, cyi) = (cx.round() as i64, cy.round() as i64);
+
+    let mut best: Option<f64> = None;
+    for y in (cyi - radius)..=(cyi + radius) {
+        for x in (cxi - radius)..=(cxi + radius) {
+            if !in_bounds(x, y) {
+                continue;
+            }
+            let v0 = coverage.get_pixel(x as u32, y as u32)[0] as f64;
+            for (dx, dy) in [(1_i64, 0_i64), (0, 1)] {
+                let (nx, ny) = (x + dx, y + dy);
+                if !in_bounds(nx, ny) {
+                    continue;
+                }
+                let v1 = coverage.get_pixel(nx as u32, ny as u32)[0] as f64;
+                if (v1 - v0).abs() < f64::EPSILON {
+                    continue;
+                }
+                let t = (threshold as f64 - v0) / (v1 - v0);
+                if !(0.0..=1.0).contains(&t) {
+                    continue;
+                }
+                let (crossing_x, crossing_y) = (x as f64 + dx as f64 * t, y as f64 + dy as f64 * t);
+                let dist = ((crossing_x - cx).powi(2) + (crossing_y - cy).powi(2)).sqrt();
+                best = Some(best.map_or(dist, |b: f64| b.min(dist)));
+            }
+        }
+    }
+    best
+}
+
+// --subpixel: snaps output samples already close to the boundary onto the
+// coverage-implied crossing instead of leaving them wherever the binarized
+// mipmap search landed, trading the hard edge-of-a-bit distance for a
+// smoother one that tracks antialiased source art. `dst` is in half-pixels
+// of the input image (same unit `sat_dst` uses), so a crossing `dist`
+// input pixels away becomes `dist * 2.0` in that unit.
+fn refine_subpixel(
+    sdf: &mut image::ImageBuffer<Luma<DstT>, Vec<DstT>>,
+    coverage: &GrayImage,
+    threshold: u8,
+) {
+    let (sdf_w, sdf_h) = sdf.dimensions();
+    let (cov_w, cov_h) = coverage.dimensions();
+    let scale_x = cov_w as f64 / sdf_w as f64;
+    let scale_y = cov_h as f64 / sdf_h as f64;
+    let block = scale_x.max(scale_y);
+    // A straddling pair can be anywhere in the input-pixel block an output
+    // sample downsamples, so search that whole block's radius around it.
+    let radius = (block / 2.0).ceil().max(1.0) as i64;
+    // One output pixel is worth `block` input pixels, i.e. `block * 2.0` in
+    // sat_dst's half-input-pixel unit; beyond that the coarse mipmap search
+    // already has about the same resolution-driven error, so there's
+    // nothing for the coverage crossing to correct.
+    let near_boundary = block * 2.0;
+    for oy in 0..sdf_h {
+        for ox in 0..sdf_w {
+            let dst = sdf.get_pixel(ox, oy)[0];
+            if dst.abs() >= near_boundary {
+                continue;
+            }
+            let cx = (ox as f64 + 0.5) * scale_x - 0.5;
+            let cy = (oy as f64 + 0.5) * scale_y - 0.5;
+            if let Some(dist) = nearest_coverage_crossing(coverage, cx, cy, threshold, radius) {
+                let sign = if dst >= 0.0 { 1.0 } else { -1.0 };
+                sdf.put_pixel(ox, oy, Luma([sign * dist * 2.0]));
+            }
+        }
+    }
+}
+
+// Shared by the "tiff"/"tiff16" and "dds" output paths: same saturation
+// scaling the raw "u16"/"png16" paths already use.
+fn dst_to_u16(dst: DstT, sat_dst: DstT) -> u16 {
+    let mut scaled = dst / sat_dst * 32767_f64;
+    if scaled < -32767_f64 {
+        scaled = -32767_f64;
+    } else if scaled > 32767_f64 {
+        scaled = 32767_f64;
+    }
+    (scaled as i32 + 32767) as u16
+}
+
+// Box-downsamples the full-resolution SDF into a mipmap chain (full size
+// first, halving each axis down to a single pixel), so "tiff" and "dds"
+// output can embed the whole chain without re-running the generator.
+fn build_sdf_mip_chain(
+    sdf: &image::ImageBuffer<Luma<DstT>, Vec<DstT>>,
+) -> Vec<image::ImageBuffer<Luma<DstT>, Vec<DstT>>> {
+    let mut levels = vec![sdf.clone()];
+    loop {
+        let (w, h) = levels.last().unwrap().dimensions();
+        if w <= 1 || h <= 1 {
+            break;
+        }
+        let prev = levels.last().unwrap();
+        let (nw, nh) = (w / 2, h / 2);
+        let next = image::ImageBuffer::from_fn(nw, nh, |x, y| {
+            let sum = prev.get_pixel(x * 2, y * 2)[0]
+                + prev.get_pixel(x * 2 + 1, y * 2)[0]
+                + prev.get_pixel(x * 2, y * 2 + 1)[0]
+                + prev.get_pixel(x * 2 + 1, y * 2 + 1)[0];
+            Luma([sum / 4_f64])
+        });
+        levels.push(next);
+    }
+    levels
+}
+
+// Writes one page of a multipage TIFF, applying whichever compressor the
+// "--tiff-compression" option selected. The tiff crate only lets the
+// compressor be chosen at the type level, so we dispatch on the option
+// string here instead of threading a trait object through.
+fn write_tiff_page<W, C>(
+    encoder: &mut TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    data: &[<C as colortype::ColorType>::Inner],
+    compression_name: &str,
+) where
+    W: Write + std::io::Seek,
+    C: colortype::ColorType,
+{
+    match compression_name {
+        "deflate" => encoder
+            .new_image_with_compression::<C, _>(width, height, compression::Deflate::default())
+            .unwrap()
+            .write_data(data)
+            .unwrap(),
+        "lzw" => encoder
+            .new_image_with_compression::<C, _>(width, height, compression::Lzw)
+            .unwrap()
+            .write_data(data)
+            .unwrap(),
+        "packbits" => encoder
+            .new_image_with_compression::<C, _>(width, height, compression::Packbits)
+            .unwrap()
+            .write_data(data)
+            .unwrap(),
+        _ => encoder
+            .new_image::<C>(width, height)
+            .unwrap()
+            .write_data(data)
+            .unwrap(),
+    }
+}
+
+// DDS constants, from the DDS_HEADER / DDS_PIXELFORMAT layout documented by
+// Microsoft: https://learn.microsoft.com/windows/win32/direct3ddds/dds-header
+const DDS_MAGIC: u32 = 0x2053_4444; // "DDS "
+const DDS_HEADER_SIZE: u32 = 124;
+const DDS_PIXELFORMAT_SIZE: u32 = 32;
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDSD_PITCH: u32 = 0x8;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+
+// Writes a single-channel (luminance) DDS texture with a full mip chain,
+// scaling each level by `sat_dst` exactly like the "u16"/"png16" paths. DDS
+// has no float luminance format in wide use, so "r16" is the finest option;
+// "r8" matches the regular "png" precision.
+fn write_dds<W: Write>(
+    w: &mut W,
+    mips: &[image::ImageBuffer<Luma<DstT>, Vec<DstT>>],
+    sat_dst: DstT,
+    format: &str,
+) -> std::io::Result<()> {
+    let bits_per_pixel: u32 = if format == "r16" { 16 } else { 8 };
+    let (width, height) = mips[0].dimensions();
+
+    w.write_u32::<LittleEndian>(DDS_MAGIC)?;
+    w.write_u32::<LittleEndian>(DDS_HEADER_SIZE)?;
+    w.write_u32::<LittleEndian>(
+        DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT | DDSD_PITCH,
+    )?;
+    w.write_u32::<LittleEndian>(height)?;
+    w.write_u32::<LittleEndian>(width)?;
+    w.write_u32::<LittleEndian>(width * bits_per_pixel / 8)?; // dwPitchOrLinearSize
+    w.write_u32::<LittleEndian>(0)?; // dwDepth
+    w.write_u32::<LittleEndian>(mips.len() as u32)?; // dwMipMapCount
+    for _ in 0..11 {
+        w.write_u32::<LittleEndian>(0)?; // dwReserved1
+    }
+    // DDS_PIXELFORMAT
+    w.write_u32::<LittleEndian>(DDS_PIXELFORMAT_SIZE)?;
+    w.write_u32::<LittleEndian>(DDPF_LUMINANCE)?;
+    w.write_u32::<LittleEndian>(0)?; // dwFourCC, unused for luminance formats
+    w.write_u32::<LittleEndian>(bits_per_pixel)?;
+    w.write_u32::<LittleEndian>(if bits_per_pixel == 16 { 0xffff } else { 0xff })?; // dwRBitMask
+    w.write_u32::<LittleEndian>(0)?; // dwGBitMask
+    w.write_u32::<LittleEndian>(0)?; // dwBBitMask
+    w.write_u32::<LittleEndian>(0)?; // dwABitMask
+    w.write_u32::<LittleEndian>(DDSCAPS_TEXTURE | DDSCAPS_COMPLEX | DDSCAPS_MIPMAP)?;
+    w.write_u32::<LittleEndian>(0)?; // dwCaps2
+    w.write_u32::<LittleEndian>(0)?; // dwCaps3
+    w.write_u32::<LittleEndian>(0)?; // dwCaps4
+    w.write_u32::<LittleEndian>(0)?; // dwReserved2
+
+    for mip in mips {
+        for px in mip.pixels() {
+            if bits_per_pixel == 16 {
+                w.write_u16::<LittleEndian>(dst_to_u16(px[0], sat_dst))?;
+            } else {
+                w.write_u8((dst_to_u16(px[0], sat_dst) >> 8) as u8)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Decodes `encoded` back through the `image` crate and checks it carries
+// the same samples as `raw` (the buffer we asked the encoder to encode),
+// so a bug in our hand-rolled PNG writer fails loudly instead of silently
+// shipping a corrupted file.
+fn optimized_png_round_trips(encoded: &[u8], raw: &[u8], bit_depth: u8) -> bool {
+    let decoded = match image::load_from_memory(encoded) {
+        Ok(img) => img,
+        Err(_) => return false,
+    };
+    if bit_depth == 16 {
+        let expected: Vec<u16> = raw
+            .chunks_exact(2)
+            .map(|le| u16::from_le_bytes([le[0], le[1]]))
+            .collect();
+        decoded.to_luma16().into_raw() == expected
+    } else {
+        decoded.to_luma8().into_raw() == raw
+    }
+}
+
+// Encodes `raw` both the normal way and through the adaptive filter search
+// in `png_filter`, keeping the smaller (optimized output always wins in
+// practice for SDF gradients, but we don't want to bet the file on it),
+// and prints the before/after byte counts when `verbose`.
+fn write_optimized_png(mut outf: File, raw: &[u8], width: u32, height: u32, bit_depth: u8, verbose: bool) {
+    let color_type = if bit_depth == 16 {
+        image::ColorType::L16
+    } else {
+        image::ColorType::L8
+    };
+    let mut naive = Vec::new();
+    image::codecs::png::PngEncoder::new(&mut naive)
+        .write_image(raw, width, height, color_type)
+        .unwrap();
+
+    let mut optimized = Vec::new();
+    png_filter::encode_optimized_png(&mut optimized, raw, width, height, bit_depth).unwrap();
+    let optimized_is_valid = optimized_png_round_trips(&optimized, raw, bit_depth);
+    if !optimized_is_valid {
+        eprintln!(
+            "warning: optimized png failed to round-trip, falling back to the unoptimized encoder"
+        );
+    }
+
+    if verbose {
+        println!(
+            "png optimization: {} bytes -> {} bytes ({:+.1}%).",
+            naive.len(),
+            optimized.len(),
+            100.0 * (optimized.len() as f64 - naive.len() as f64) / naive.len() as f64
+        );
+    }
+
+    let best = if optimized_is_valid && optimized.len() < naive.len() {
+        &optimized
+    } else {
+        &naive
+    };
+    outf.write_all(best).unwrap();
+}
+
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let program_name = args[0].clone();
@@ -34,10 +343,16 @@ fn main() {
     let mut opts = Options::new();
     opts.optflag("h", "help", "print help");
     opts.optflag("v", "verbose", "show what the program is doing");
+    opts.optflag("", "optimize", "run a lossless adaptive per-scanline filter search before encoding 'png'/'png16' output, trading encode time for a smaller file");
     opts.optopt ("s","size","size of the output signed distance field image, must be a power of 2. Defaults to input size / 4","OUTPUT_SIZE");
     opts.optopt ( "","maxdst","saturation distance (i.e. 'most far away meaningful distance') in half pixels of the input image. Defaults to input size / 4","SATURATION_DISTANCE");
     opts.optopt ( "","save-mipmaps","save the mipmaps used for accelerated calculation to BASENAMEi.png, where 'i' is the mipmap level","BASENAME");
-    opts.optopt ("t","type","One of 'png', 'png16', 'u16', 'f32', 'f64'. f32 and f64 are raw floating point formats, u16 is raw unsigned 16 bit integers. Default: png","TYPE");
+    opts.optopt ("t","type","One of 'png', 'png16', 'u16', 'f32', 'f64', 'tiff', 'tiff16', 'tifff32', 'dds'. f32 and f64 are raw floating point formats, u16 is raw unsigned 16 bit integers, 'tiff'/'tiff16' and 'tifff32' write a multipage TIFF carrying the full SDF mip chain as u16 or f32 samples respectively, 'dds' writes a DDS texture with an embedded mip chain. Default: png","TYPE");
+    opts.optopt ("","tiff-compression","compression used for 'tiff'/'tiff16'/'tifff32' output: one of 'none', 'deflate', 'lzw', 'packbits'. Default: none","COMPRESSION");
+    opts.optopt ("","dds-format","pixel format used for 'dds' output: one of 'r8', 'r16'. Default: r8","FORMAT");
+    opts.optopt ("","threads","cap the size of the global rayon thread pool, in case something in this binary's dependency tree draws from it. This does NOT implement a parallel SDF search: calculate_sdf lives in the sdfgen library crate, outside this checkout, so whether it uses rayon's global pool at all is unverified here. Defaults to rayon's own choice (usually the number of CPUs)","N");
+    opts.optopt ("","threshold","grayscale value (0-255) at or above which a pixel is binarized to 1 (the white side); darker pixels become 0. Default: 127 (mid-gray)","T");
+    opts.optflag ("","subpixel","refine SDF samples near the zero crossing using the input's original grayscale coverage instead of leaving them wherever the binarized search placed them, placing the boundary at the 0.5-coverage crossing between adjacent pixels");
     if args.len() == 1 {
         print_usage(&program_name, &opts);
         return;
@@ -55,6 +370,7 @@ fn main() {
     let input_image_name = &parsed_opts.free[0];
     let output_image_name = &parsed_opts.free[1];
     let verbose = parsed_opts.opt_present("verbose");
+    let optimize = parsed_opts.opt_present("optimize");
 
     if verbose {
         println!("Loading input image '{}'.", input_image_name);
@@ -94,11 +410,23 @@ fn main() {
     }
     let (input_size, _) = img.dimensions();
 
+    let threshold: u8 = match parsed_opts.opt_str("threshold") {
+        Some(s) => s.parse::<u8>().unwrap(),
+        None => 127,
+    };
+    let subpixel = parsed_opts.opt_present("subpixel");
+    // calculate_sdf only ever sees the binarized mipmap, so --subpixel can't
+    // reach into its heap search (that lives in sdfgen::sdf_algorithm,
+    // outside this checkout). Instead we keep the original grayscale here
+    // and, once the coarse SDF comes back, re-snap samples near the zero
+    // crossing onto the 0.5-coverage crossing this coverage implies.
+    let coverage_img = if subpixel { Some(img.clone()) } else { None };
+
     if verbose {
-        println!("Converting image to binary.");
+        println!("Converting image to binary with threshold {}.", threshold);
     }
     for px in img.pixels_mut() {
-        px[0] = bw_to_bits(px[0]);
+        px[0] = binarize(px[0], threshold);
     }
 
     if verbose {
@@ -140,8 +468,25 @@ fn main() {
             sdf_size, sat_dst
         );
     }
+    // calculate_sdf lives in the sdfgen library crate (pulled in via
+    // `extern crate sdfgen`, not part of this repository), so whether and
+    // how it parallelizes its search is outside our control here. This
+    // only bounds the size of rayon's global pool, for whenever it (or any
+    // other rayon consumer linked into this binary) does use one.
+    if let Some(n) = parsed_opts.opt_str("threads") {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(n.parse::<usize>().unwrap())
+            .build_global()
+            .expect("failed to configure rayon thread pool");
+    }
     let mipmap_arc = std::sync::Arc::new(mipmap);
-    let sdf = calculate_sdf(mipmap_arc, sdf_size);
+    let mut sdf = calculate_sdf(mipmap_arc, sdf_size);
+    if let Some(coverage_img) = &coverage_img {
+        if verbose {
+            println!("Refining boundary samples against subpixel coverage.");
+        }
+        refine_subpixel(&mut sdf, coverage_img, threshold);
+    }
     if verbose {
         println!("Doing a final color space conversion.");
     }
@@ -183,30 +528,21 @@ fn main() {
                 );
             }
             let outf = File::create(output_image_name).unwrap();
-            let pngenc = image::codecs::png::PngEncoder::<std::fs::File>::new(outf);
-            pngenc
-                .write_image(sdf_u8.into_raw().as_ref(), w, h, image::ColorType::L8)
-                .unwrap();
+            if optimize {
+                write_optimized_png(outf, sdf_u8.into_raw().as_ref(), w, h, 8, verbose);
+            } else {
+                let pngenc = image::codecs::png::PngEncoder::<std::fs::File>::new(outf);
+                pngenc
+                    .write_image(sdf_u8.into_raw().as_ref(), w, h, image::ColorType::L8)
+                    .unwrap();
+            }
         }
-        // TODO: remove code duplication here
         "u16" | "png16" => {
             let (w, h) = &sdf.dimensions();
             let mut buf = vec![];
 
-            let writer = |b: &mut Vec<u8>, v| b.write_u16::<LittleEndian>(v);
-
             for px in sdf.into_raw() {
-                let mut dst = px;
-                dst = dst / sat_dst * 32767_f64;
-                if dst < -32767_f64 {
-                    dst = -32767_f64;
-                } else if dst > 32767_f64 {
-                    dst = 32767_f64;
-                }
-                debug_assert!(dst <= 32767_f64);
-                debug_assert!(dst >= -32767_f64);
-                let v: u16 = (dst as i32 + 32767) as u16;
-                writer(&mut buf, v).unwrap();
+                buf.write_u16::<LittleEndian>(dst_to_u16(px, sat_dst)).unwrap();
             }
             if verbose {
                 println!(
@@ -215,9 +551,12 @@ fn main() {
                 );
             }
 
-            let mut outf = File::create(output_image_name).unwrap();
+            let outf = File::create(output_image_name).unwrap();
             if output_type == "u16" {
+                let mut outf = outf;
                 outf.write_all(buf.as_ref()).unwrap();
+            } else if optimize {
+                write_optimized_png(outf, buf.as_ref(), *w, *h, 16, verbose);
             } else {
                 let pngenc = image::codecs::png::PngEncoder::<std::fs::File>::new(outf);
                 pngenc
@@ -253,8 +592,250 @@ fn main() {
             let mut outf = File::create(output_image_name).unwrap();
             outf.write_all(buf.as_ref()).unwrap();
         }
+        "tiff" | "tiff16" | "tifff32" => {
+            let mips = build_sdf_mip_chain(&sdf);
+            let compression_name = parsed_opts
+                .opt_str("tiff-compression")
+                .unwrap_or_else(|| "none".to_string());
+            match compression_name.as_ref() {
+                "none" | "deflate" | "lzw" | "packbits" => {}
+                _ => panic!("Unknown tiff compression: {}", compression_name),
+            }
+            if verbose {
+                println!(
+                    "Saving {} level signed distance field mip chain in {} format ({} compression) as '{}'.",
+                    mips.len(), output_type, compression_name, output_image_name
+                );
+            }
+            let outf = File::create(output_image_name).unwrap();
+            let mut encoder = TiffEncoder::new(outf).unwrap();
+            for mip in &mips {
+                let (w, h) = mip.dimensions();
+                if output_type == "tifff32" {
+                    let page: Vec<f32> = mip.pixels().map(|px| px[0] as f32).collect();
+                    write_tiff_page::<_, colortype::Gray32Float>(
+                        &mut encoder,
+                        w,
+                        h,
+                        &page,
+                        &compression_name,
+                    );
+                } else {
+                    let page: Vec<u16> = mip.pixels().map(|px| dst_to_u16(px[0], sat_dst)).collect();
+                    write_tiff_page::<_, colortype::Gray16>(
+                        &mut encoder,
+                        w,
+                        h,
+                        &page,
+                        &compression_name,
+                    );
+                }
+            }
+        }
+        "dds" => {
+            let mips = build_sdf_mip_chain(&sdf);
+            let dds_format = parsed_opts
+                .opt_str("dds-format")
+                .unwrap_or_else(|| "r8".to_string());
+            match dds_format.as_ref() {
+                "r8" | "r16" => {}
+                _ => panic!("Unknown dds format: {}", dds_format),
+            }
+            if verbose {
+                println!(
+                    "Saving {} level signed distance field DDS texture ({}) as '{}'.",
+                    mips.len(), dds_format, output_image_name
+                );
+            }
+            let mut outf = File::create(output_image_name).unwrap();
+            write_dds(&mut outf, &mips, sat_dst, &dds_format).unwrap();
+        }
         _ => {
             panic!("Unknown output format: {}", output_type);
         }
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use byteorder::ReadBytesExt;
+    use std::io::Cursor;
+    use std::io::Seek;
+    use std::io::SeekFrom;
+
+    // Asserts the DDS_HEADER / DDS_PIXELFORMAT fields land at the byte
+    // offsets the format spec fixes them at, so a future refactor of
+    // `write_dds` can't silently shift something and still "look" valid.
+    #[test]
+    fn write_dds_header_matches_expected_layout() {
+        let mips = vec![
+            image::ImageBuffer::from_pixel(2, 2, Luma([0_f64])),
+            image::ImageBuffer::from_pixel(1, 1, Luma([0_f64])),
+        ];
+        let mut buf = Vec::new();
+        write_dds(&mut buf, &mips, 1.0, "r8").unwrap();
+
+        let mut c = Cursor::new(&buf);
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), DDS_MAGIC);
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), DDS_HEADER_SIZE);
+        let flags = c.read_u32::<LittleEndian>().unwrap();
+        assert_eq!(
+            flags,
+            DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_MIPMAPCOUNT | DDSD_PITCH
+        );
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 2); // dwHeight
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 2); // dwWidth
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 2); // dwPitchOrLinearSize (r8: width * 8 / 8)
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 0); // dwDepth
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 2); // dwMipMapCount
+
+        c.set_position(c.position() + 11 * 4); // dwReserved1
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), DDS_PIXELFORMAT_SIZE);
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), DDPF_LUMINANCE);
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 0); // dwFourCC
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 8); // dwRGBBitCount
+        assert_eq!(c.read_u32::<LittleEndian>().unwrap(), 0xff); // dwRBitMask
+
+        // header (124 bytes) + magic (4 bytes) should be followed by the
+        // 2x2 then 1x1 R8 mip levels, with nothing else in between.
+        assert_eq!(buf.len() as u32, 4 + DDS_HEADER_SIZE + 2 * 2 + 1 * 1);
+    }
+
+    // A flat-black image with a single step up to white between x=3 and
+    // x=4 straddles `threshold` on that one edge; every other adjacent
+    // pair is either all-black or all-white and shouldn't report a crossing.
+    fn step_coverage() -> GrayImage {
+        image::ImageBuffer::from_fn(8, 8, |x, _y| Luma([if x < 4 { 0_u8 } else { 255 }]))
+    }
+
+    #[test]
+    fn nearest_coverage_crossing_finds_straddling_edge() {
+        let coverage = step_coverage();
+        let dist = nearest_coverage_crossing(&coverage, 3.0, 3.0, 127, 2).unwrap();
+        // threshold 127 sits just below the midpoint of the 0->255 step, so
+        // the crossing lands slightly short of x=3.5, less than 1 px away.
+        assert!(dist > 0.0 && dist < 1.0, "unexpected distance: {dist}");
+    }
+
+    #[test]
+    fn nearest_coverage_crossing_none_when_no_neighbor_straddles() {
+        let coverage = step_coverage();
+        // x=0 is deep in the flat-black region; with radius 1 nothing
+        // within reach crosses the threshold.
+        assert!(nearest_coverage_crossing(&coverage, 0.0, 3.0, 127, 1).is_none());
+    }
+
+    #[test]
+    fn nearest_coverage_crossing_ignores_out_of_bounds_neighbors() {
+        let coverage = step_coverage();
+        // Centered at the image corner, most of the search box falls
+        // outside `coverage` and is still too far from the step to reach
+        // it; should just report "no crossing in range" rather than
+        // panicking or reading garbage from out-of-bounds coordinates.
+        assert!(nearest_coverage_crossing(&coverage, 0.0, 0.0, 127, 2).is_none());
+    }
+
+    #[test]
+    fn nearest_coverage_crossing_respects_radius() {
+        let coverage = step_coverage();
+        // The crossing is 3-4 px away from x=0; a radius of 1 shouldn't
+        // reach it, but a radius big enough to cover the block should.
+        assert!(nearest_coverage_crossing(&coverage, 0.0, 3.0, 127, 1).is_none());
+        assert!(nearest_coverage_crossing(&coverage, 0.0, 3.0, 127, 4).is_some());
+    }
+
+    #[test]
+    fn refine_subpixel_snaps_onto_coverage_gradient() {
+        let coverage = step_coverage();
+        // sdf has the same resolution as coverage (scale 1.0), all samples
+        // initially inside the near-boundary band so every one gets visited.
+        let mut sdf = image::ImageBuffer::from_fn(8, 8, |x, _y| {
+            Luma([if x < 4 { -1.0_f64 } else { 1.0 }])
+        });
+        refine_subpixel(&mut sdf, &coverage, 127);
+
+        // Far from the step the coarse value already agreed with its own
+        // sign and had no nearby crossing to snap to within radius 1, so it
+        // should be left untouched.
+        assert_eq!(sdf.get_pixel(0, 3)[0], -1.0);
+
+        // x=3 and x=4 straddle the step; both should have snapped to a
+        // small magnitude whose sign matches the coarse estimate's side.
+        let left = sdf.get_pixel(3, 3)[0];
+        let right = sdf.get_pixel(4, 3)[0];
+        assert!(left < 0.0 && left.abs() < 1.5, "left sample: {left}");
+        assert!(right > 0.0 && right.abs() < 1.5, "right sample: {right}");
+    }
+
+    // "tiff16" writes each mip level as a Gray16 page via `write_tiff_page`;
+    // round-trip through the `tiff` crate's own decoder (rather than just
+    // re-reading the bytes we wrote) so a page-count/dimension/sample-format
+    // mismatch shows up the same way it would for a real consumer.
+    #[test]
+    fn tiff16_round_trips_through_tiff_crate_decoder() {
+        let mips = vec![
+            image::ImageBuffer::from_fn(4, 4, |x, y| Luma([(x + y) as f64])),
+            image::ImageBuffer::from_pixel(2, 2, Luma([0_f64])),
+        ];
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            for mip in &mips {
+                let (w, h) = mip.dimensions();
+                let page: Vec<u16> = mip.pixels().map(|px| dst_to_u16(px[0], 8.0)).collect();
+                write_tiff_page::<_, colortype::Gray16>(&mut encoder, w, h, &page, "none");
+            }
+        }
+
+        buf.seek(SeekFrom::Start(0)).unwrap();
+        let mut decoder = tiff::decoder::Decoder::new(buf).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(16));
+        let first_page = match decoder.read_image().unwrap() {
+            tiff::decoder::DecodingResult::U16(samples) => samples,
+            other => panic!("expected U16 samples, got {other:?}"),
+        };
+        let expected: Vec<u16> = mips[0].pixels().map(|px| dst_to_u16(px[0], 8.0)).collect();
+        assert_eq!(first_page, expected);
+
+        assert!(decoder.more_images());
+        decoder.next_image().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert!(!decoder.more_images());
+    }
+
+    // Same as above for "tifff32", which writes Gray32Float pages instead.
+    #[test]
+    fn tifff32_round_trips_through_tiff_crate_decoder() {
+        let mips = vec![
+            image::ImageBuffer::from_fn(4, 4, |x, y| Luma([(x as f64) - (y as f64)])),
+            image::ImageBuffer::from_pixel(2, 2, Luma([0_f64])),
+        ];
+        let mut buf = Cursor::new(Vec::new());
+        {
+            let mut encoder = TiffEncoder::new(&mut buf).unwrap();
+            for mip in &mips {
+                let (w, h) = mip.dimensions();
+                let page: Vec<f32> = mip.pixels().map(|px| px[0] as f32).collect();
+                write_tiff_page::<_, colortype::Gray32Float>(&mut encoder, w, h, &page, "none");
+            }
+        }
+
+        buf.seek(SeekFrom::Start(0)).unwrap();
+        let mut decoder = tiff::decoder::Decoder::new(buf).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (4, 4));
+        assert_eq!(decoder.colortype().unwrap(), tiff::ColorType::Gray(32));
+        let first_page = match decoder.read_image().unwrap() {
+            tiff::decoder::DecodingResult::F32(samples) => samples,
+            other => panic!("expected F32 samples, got {other:?}"),
+        };
+        let expected: Vec<f32> = mips[0].pixels().map(|px| px[0] as f32).collect();
+        assert_eq!(first_page, expected);
+
+        assert!(decoder.more_images());
+        decoder.next_image().unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (2, 2));
+        assert!(!decoder.more_images());
+    }
+}